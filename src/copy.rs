@@ -0,0 +1,125 @@
+//! Bulk loading through `COPY ... FROM STDIN BINARY`.
+//!
+//! Rows for a table are streamed into a temporary staging table shaped like
+//! the `gtfs.*` table and then merged with `INSERT ... ON CONFLICT`, so a
+//! repeated key within a feed still ends up as a single row, the last one.
+//! Everything runs inside the transaction of the feed being imported, and
+//! every row gets the feed_version_id of that import.
+
+use std::{pin::Pin, time::Instant};
+use tokio_postgres::{binary_copy::BinaryCopyInWriter, types::{ToSql, Type}, Error, Transaction};
+
+use crate::report::ImportError;
+
+pub struct Table {
+    pub name: &'static str,
+    pub columns: &'static [&'static str],
+    /// Conflict target of the merge, the columns or expressions of the
+    /// table's primary key or unique index. Empty for tables without one,
+    /// which are appended to as-is.
+    pub key: &'static [&'static str],
+}
+
+impl Table {
+    fn error(&self, error: Error) -> ImportError {
+        ImportError::Load { table: Some(self.name), error }
+    }
+}
+
+pub struct CopyIn<'a> {
+    transaction: &'a Transaction<'a>,
+    table: &'a Table,
+    writer: Pin<Box<BinaryCopyInWriter>>,
+    rows: u64,
+    started: Instant,
+}
+
+impl<'a> CopyIn<'a> {
+    pub async fn start(transaction: &'a Transaction<'a>, table: &'a Table, feed_version_id: i64) -> Result<CopyIn<'a>, ImportError> {
+        let columns = table.columns.join(", ");
+        transaction.batch_execute(&format!("
+            DROP TABLE IF EXISTS pg_temp.staging_{name};
+            CREATE TEMP TABLE staging_{name} (LIKE gtfs.{name});
+            ALTER TABLE pg_temp.staging_{name} ALTER COLUMN feed_version_id SET DEFAULT {feed_version_id};
+            ALTER TABLE pg_temp.staging_{name} ADD COLUMN staging_row bigserial;
+        ", name = table.name, feed_version_id = feed_version_id)).await.map_err(|e| table.error(e))?;
+        // Let the server resolve the column types, which also covers
+        // extension types such as PostGIS geometry that have no fixed oid.
+        let types = transaction
+            .prepare(&format!("SELECT {} FROM pg_temp.staging_{}", columns, table.name))
+            .await
+            .map_err(|e| table.error(e))?
+            .columns()
+            .iter()
+            .map(|column| column.type_().clone())
+            .collect::<Vec<Type>>();
+        let sink = transaction
+            .copy_in(&format!("COPY pg_temp.staging_{} ({}) FROM STDIN BINARY", table.name, columns))
+            .await
+            .map_err(|e| table.error(e))?;
+        Ok(CopyIn {
+            transaction,
+            table,
+            writer: Box::pin(BinaryCopyInWriter::new(sink, &types)),
+            rows: 0,
+            started: Instant::now(),
+        })
+    }
+
+    pub async fn write(&mut self, row: &[&(dyn ToSql + Sync)]) -> Result<(), ImportError> {
+        self.writer.as_mut().write(row).await.map_err(|e| self.table.error(e))?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Ends the copy, merges the staged rows into `gtfs.<table>` and prints
+    /// the throughput for the table.
+    pub async fn finish(mut self, onestop_feed_id: &str) -> Result<u64, ImportError> {
+        let table = self.table;
+        self.writer.as_mut().finish().await.map_err(|e| table.error(e))?;
+        let columns = format!("feed_version_id, {}", table.columns.join(", "));
+        let merge = if table.key.is_empty() {
+            format!("
+                INSERT INTO gtfs.{name} ({columns})
+                SELECT {columns} FROM pg_temp.staging_{name};",
+                name = table.name, columns = columns)
+        } else {
+            let key = table.key.join(", ");
+            let updates = table.columns
+                .iter()
+                .filter(|column| !table.key.contains(column))
+                .map(|column| format!("{column} = EXCLUDED.{column}"))
+                .collect::<Vec<String>>();
+            let action = if updates.is_empty() {
+                "NOTHING".to_string()
+            } else {
+                format!("UPDATE SET {}", updates.join(", "))
+            };
+            // A feed can repeat a key; DISTINCT ON keeps a single row per key
+            // since ON CONFLICT cannot touch the same row twice. staging_row
+            // counts the rows as copied, so the last one in the file wins.
+            format!("
+                INSERT INTO gtfs.{name} ({columns})
+                SELECT DISTINCT ON ({key}) {columns} FROM pg_temp.staging_{name}
+                ORDER BY {key}, staging_row DESC
+                ON CONFLICT ({key}) DO {action};",
+                name = table.name, columns = columns, key = key, action = action)
+        };
+        self.transaction.batch_execute(&merge).await.map_err(|e| table.error(e))?;
+        self.transaction
+            .batch_execute(&format!("DROP TABLE pg_temp.staging_{};", table.name))
+            .await
+            .map_err(|e| table.error(e))?;
+
+        let elapsed = self.started.elapsed();
+        println!(
+            "{}: {} {} rows in {:.2?} ({:.0} rows/s)",
+            onestop_feed_id,
+            table.name,
+            self.rows,
+            elapsed,
+            self.rows as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+        );
+        Ok(self.rows)
+    }
+}
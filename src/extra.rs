@@ -0,0 +1,305 @@
+//! Readers for the GTFS files that gtfs_structures does not parse, using the
+//! same zip (or directory) the feed was loaded from.
+
+use gtfs_structures::{Error, RawTranslation};
+use serde::{de::{DeserializeOwned, Error as _}, Deserialize as _, Deserializer};
+use serde_derive::Deserialize;
+use std::{fs::{self, File}, io::Read, path::{Path, PathBuf}};
+use zip::ZipArchive;
+
+#[derive(Debug, Deserialize)]
+pub struct Level {
+    pub level_id: String,
+    pub level_index: f64,
+    pub level_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Attribution {
+    pub attribution_id: Option<String>,
+    pub agency_id: Option<String>,
+    pub route_id: Option<String>,
+    pub trip_id: Option<String>,
+    pub organization_name: String,
+    pub is_producer: Option<i32>,
+    pub is_operator: Option<i32>,
+    pub is_authority: Option<i32>,
+    pub attribution_url: Option<String>,
+    pub attribution_email: Option<String>,
+    pub attribution_phone: Option<String>,
+}
+
+/// A row of transfers.txt. gtfs_structures merges transfers into stops and
+/// leaves out the route and trip columns, and the rows between trips that
+/// have no stops.
+#[derive(Debug, Deserialize)]
+pub struct Transfer {
+    pub from_stop_id: Option<String>,
+    pub to_stop_id: Option<String>,
+    pub from_route_id: Option<String>,
+    pub to_route_id: Option<String>,
+    pub from_trip_id: Option<String>,
+    pub to_trip_id: Option<String>,
+    pub transfer_type: Option<i32>,
+    pub min_transfer_time: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Timeframe {
+    pub timeframe_group_id: String,
+    #[serde(default, deserialize_with = "deserialize_optional_time")]
+    pub start_time: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_optional_time")]
+    pub end_time: Option<u32>,
+    pub service_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FareMedia {
+    pub fare_media_id: String,
+    pub fare_media_name: Option<String>,
+    pub fare_media_type: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FareProduct {
+    pub fare_product_id: String,
+    pub fare_product_name: Option<String>,
+    pub fare_media_id: Option<String>,
+    pub amount: String,
+    pub currency: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Area {
+    pub area_id: String,
+    pub area_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StopArea {
+    pub area_id: String,
+    pub stop_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Network {
+    pub network_id: String,
+    pub network_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RouteNetwork {
+    pub network_id: String,
+    pub route_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FareLegRule {
+    pub leg_group_id: Option<String>,
+    pub network_id: Option<String>,
+    pub from_area_id: Option<String>,
+    pub to_area_id: Option<String>,
+    pub from_timeframe_group_id: Option<String>,
+    pub to_timeframe_group_id: Option<String>,
+    pub fare_product_id: String,
+    pub rule_priority: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FareTransferRule {
+    pub from_leg_group_id: Option<String>,
+    pub to_leg_group_id: Option<String>,
+    pub transfer_count: Option<i32>,
+    pub duration_limit: Option<i32>,
+    pub duration_limit_type: Option<i32>,
+    pub fare_transfer_type: i32,
+    pub fare_product_id: Option<String>,
+}
+
+/// Everything read from a feed on top of [gtfs_structures::Gtfs].
+pub struct Extra {
+    pub levels: Vec<Level>,
+    // gtfs_structures only looks for translations.txt in unzipped feeds.
+    pub translations: Vec<RawTranslation>,
+    pub attributions: Vec<Attribution>,
+    pub transfers: Vec<Transfer>,
+    pub timeframes: Vec<Timeframe>,
+    pub fare_media: Vec<FareMedia>,
+    pub fare_products: Vec<FareProduct>,
+    pub areas: Vec<Area>,
+    pub stop_areas: Vec<StopArea>,
+    pub networks: Vec<Network>,
+    pub route_networks: Vec<RouteNetwork>,
+    pub fare_leg_rules: Vec<FareLegRule>,
+    pub fare_transfer_rules: Vec<FareTransferRule>,
+}
+
+impl Extra {
+    pub fn from_path(path: &Path) -> Result<Extra, Error> {
+        let mut archive = Archive::open(path)?;
+        Ok(Extra {
+            levels: archive.read("levels.txt")?,
+            translations: archive.read("translations.txt")?,
+            attributions: archive.read("attributions.txt")?,
+            transfers: archive.read("transfers.txt")?,
+            timeframes: archive.read("timeframes.txt")?,
+            fare_media: archive.read("fare_media.txt")?,
+            fare_products: archive.read("fare_products.txt")?,
+            areas: archive.read("areas.txt")?,
+            stop_areas: archive.read("stop_areas.txt")?,
+            networks: archive.read("networks.txt")?,
+            route_networks: archive.read("route_networks.txt")?,
+            fare_leg_rules: archive.read("fare_leg_rules.txt")?,
+            fare_transfer_rules: archive.read("fare_transfer_rules.txt")?,
+        })
+    }
+}
+
+/// Parses an optional `H:MM:SS` time into seconds since the start of the
+/// service day, like gtfs_structures does for stop_times.txt.
+fn deserialize_optional_time<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = Option::<String>::deserialize(deserializer)?;
+    match s.as_deref() {
+        None | Some("") => Ok(None),
+        Some(s) => {
+            let mut parts = s.split(':').map(str::parse::<u32>);
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(Ok(h)), Some(Ok(m)), Some(Ok(sec)), None) => h
+                    .checked_mul(3600)
+                    .zip(m.checked_mul(60))
+                    .and_then(|(h, m)| h.checked_add(m)?.checked_add(sec))
+                    .map(Some)
+                    .ok_or_else(|| D::Error::custom(format!("invalid time {}", s))),
+                _ => Err(D::Error::custom(format!("invalid time {}", s))),
+            }
+        }
+    }
+}
+
+enum Archive {
+    Zip(ZipArchive<File>),
+    Directory(PathBuf),
+}
+
+impl Archive {
+    fn open(path: &Path) -> Result<Archive, Error> {
+        if path.is_dir() {
+            Ok(Archive::Directory(path.to_path_buf()))
+        } else {
+            Ok(Archive::Zip(ZipArchive::new(File::open(path)?)?))
+        }
+    }
+
+    /// Reads every row of `file_name`. A missing file is not an error since
+    /// all of the files read here are optional.
+    fn read<T: DeserializeOwned>(&mut self, file_name: &str) -> Result<Vec<T>, Error> {
+        let contents = match self {
+            Archive::Zip(archive) => {
+                // Feeds are sometimes zipped with a top level directory.
+                let index = (0..archive.len()).find(|i| {
+                    archive.name_for_index(*i).and_then(|name| Path::new(name).file_name()) == Some(file_name.as_ref())
+                });
+                match index {
+                    Some(index) => {
+                        let mut contents = String::new();
+                        archive.by_index(index)?.read_to_string(&mut contents)?;
+                        contents
+                    }
+                    None => return Ok(Vec::new()),
+                }
+            }
+            Archive::Directory(path) => match fs::read_to_string(path.join(file_name)) {
+                Ok(contents) => contents,
+                Err(_) => return Ok(Vec::new()),
+            },
+        };
+        csv::ReaderBuilder::new()
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(contents.trim_start_matches('\u{feff}').as_bytes())
+            .deserialize()
+            .collect::<Result<Vec<T>, csv::Error>>()
+            .map_err(|e| Error::CSVError {
+                file_name: file_name.to_owned(),
+                source: e,
+                line_in_error: None,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/extra").join(name)
+    }
+
+    fn check(extra: &Extra) {
+        assert_eq!(extra.levels.len(), 2);
+        assert_eq!(extra.levels[0].level_id, "L0");
+        assert_eq!(extra.levels[1].level_index, -1.5);
+        assert_eq!(extra.levels[1].level_name, None);
+        assert_eq!(extra.transfers[0].from_route_id.as_deref(), Some("R1"));
+        assert_eq!(extra.transfers[0].min_transfer_time, Some(180));
+        assert_eq!(extra.transfers[1].from_trip_id.as_deref(), Some("T1"));
+        assert_eq!(extra.transfers[1].transfer_type, None);
+        assert_eq!(extra.transfers[2].from_stop_id, None);
+        assert_eq!(extra.transfers[2].to_trip_id.as_deref(), Some("T3"));
+        assert_eq!(extra.timeframes[0].start_time, Some(7 * 3600));
+        assert_eq!(extra.timeframes[0].end_time, Some(9 * 3600 + 30 * 60));
+        assert_eq!(extra.timeframes[1].start_time, None);
+    }
+
+    #[test]
+    fn directory_feeds() {
+        let extra = Extra::from_path(&fixture("feed")).unwrap();
+        check(&extra);
+        assert!(extra.translations.is_empty());
+        assert!(extra.fare_transfer_rules.is_empty());
+    }
+
+    /// The fixture zipped under a top level directory, next to a stray file
+    /// whose name only ends like one of the feed's.
+    #[test]
+    fn zipped_feeds_match_on_the_file_name() {
+        let path = std::env::temp_dir().join(format!("gtfs-schema-extra-{}.zip", std::process::id()));
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("feed/old_levels.txt", SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"level_id,level_index\nOLD,9\n").unwrap();
+        for entry in fs::read_dir(fixture("feed")).unwrap() {
+            let entry = entry.unwrap();
+            zip.start_file(format!("feed/{}", entry.file_name().to_str().unwrap()), SimpleFileOptions::default()).unwrap();
+            zip.write_all(&fs::read(entry.path()).unwrap()).unwrap();
+        }
+        zip.finish().unwrap();
+        let extra = Extra::from_path(&path);
+        fs::remove_file(&path).unwrap();
+        let extra = extra.unwrap();
+        check(&extra);
+        assert!(extra.areas.is_empty());
+    }
+
+    #[test]
+    fn malformed_rows_name_their_file() {
+        let error = Extra::from_path(&fixture("malformed")).err().unwrap();
+        assert!(matches!(error, Error::CSVError { ref file_name, .. } if file_name == "levels.txt"), "{:?}", error);
+        let mut archive = Archive::open(&fixture("malformed")).unwrap();
+        let error = archive.read::<Timeframe>("timeframes.txt").err().unwrap();
+        assert!(matches!(error, Error::CSVError { ref file_name, .. } if file_name == "timeframes.txt"), "{:?}", error);
+        assert!(error.to_string().contains("timeframes.txt"), "{}", error);
+    }
+
+    #[test]
+    fn times_past_u32_are_rejected() {
+        let rows = "timeframe_group_id,start_time,end_time,service_id\npeak,1193047:00:00,,WK\n";
+        let error = csv::Reader::from_reader(rows.as_bytes()).deserialize::<Timeframe>().next().unwrap().err().unwrap();
+        assert!(error.to_string().contains("invalid time 1193047:00:00"), "{}", error);
+    }
+}
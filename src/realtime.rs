@@ -0,0 +1,755 @@
+//! Polls the GTFS-Realtime feeds listed in the DMFR files and keeps the
+//! latest state of each in the `gtfs_rt` schema.
+//!
+//! Every fetch replaces what was stored for that realtime feed, so the tables
+//! only ever hold the last message. Rows are keyed by the onestop_feed_id of
+//! the static feed the realtime feed is associated with through an operator,
+//! and their trip_ids and stop_ids are matched against the latest import of
+//! that static feed.
+
+mod config;
+mod dmfr;
+mod gtfs_rt;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use config::Config;
+use deadpool_postgres::{Client, Pool};
+use dmfr::{DistributedMobilityFeedRegistry, FeedSpec};
+use futures::{stream::FuturesUnordered, StreamExt};
+use gtfs_rt::FeedMessage;
+use prost::Message;
+use std::{collections::HashMap, fmt, fs, path::Path, process, time::Duration};
+use tokio::task;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    TripUpdates,
+    VehiclePositions,
+    Alerts,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::TripUpdates => "trip_updates",
+            Kind::VehiclePositions => "vehicle_positions",
+            Kind::Alerts => "alerts",
+        }
+    }
+}
+
+/// One URL of a realtime feed.
+#[derive(Clone, Debug)]
+struct Source {
+    realtime_feed_id: String,
+    /// The static feed whose ids the messages use, or the realtime feed
+    /// itself when no operator associates it with one.
+    onestop_feed_id: String,
+    kind: Kind,
+    url: String,
+}
+
+#[derive(Debug)]
+enum FetchError {
+    Http(reqwest::Error),
+    Decode(prost::DecodeError),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::Http(e) => write!(f, "cannot fetch: {}", e),
+            FetchError::Decode(e) => write!(f, "cannot decode: {}", e),
+        }
+    }
+}
+
+/// The realtime URLs of every GTFS-RT feed in the DMFR files in `dir`.
+fn sources(dir: &Path) -> Vec<Source> {
+    let mut sources = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        eprintln!("Error reading the directory");
+        return sources;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_file() || path.extension().unwrap_or_default() != "json" {
+            continue;
+        }
+        let registry = match fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|json| {
+            serde_json::from_str::<DistributedMobilityFeedRegistry>(&json).map_err(|e| e.to_string())
+        }) {
+            Ok(registry) => registry,
+            Err(e) => {
+                eprintln!("Skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        sources.extend(registry_sources(&registry));
+    }
+    sources
+}
+
+fn registry_sources(registry: &DistributedMobilityFeedRegistry) -> Vec<Source> {
+    let specs = registry.feeds
+        .iter()
+        .map(|feed| (feed.id.as_str(), feed.spec))
+        .collect::<HashMap<&str, FeedSpec>>();
+    // Operators tie a realtime feed to its static feed, either listed at the
+    // top of the file or nested under one of the feeds.
+    let operators = registry.operators
+        .iter()
+        .map(|operator| (None, operator))
+        .chain(registry.feeds.iter().flat_map(|feed| feed.operators.iter().map(move |operator| (Some(feed.id.as_str()), operator))));
+    let mut statics = HashMap::new();
+    for (parent, operator) in operators {
+        let feeds = parent
+            .into_iter()
+            .chain(operator.associated_feeds.iter().filter_map(|associated| associated.feed_onestop_id.as_deref()))
+            .collect::<Vec<&str>>();
+        if let Some(static_feed) = feeds.iter().find(|feed| specs.get(*feed) == Some(&FeedSpec::Gtfs)) {
+            for feed in feeds.iter().filter(|feed| specs.get(*feed) == Some(&FeedSpec::GtfsRt)) {
+                statics.entry(feed.to_string()).or_insert(static_feed.to_string());
+            }
+        }
+    }
+
+    let mut sources = Vec::new();
+    for feed in registry.feeds.iter().filter(|feed| feed.spec == FeedSpec::GtfsRt) {
+        let urls = [
+            (Kind::TripUpdates, feed.urls.realtime_trip_updates.as_deref()),
+            (Kind::VehiclePositions, feed.urls.realtime_vehicle_positions.as_deref()),
+            (Kind::Alerts, feed.urls.realtime_alerts.as_deref()),
+        ];
+        for (kind, url) in urls {
+            if let Some(url) = url {
+                sources.push(Source {
+                    realtime_feed_id: feed.id.clone(),
+                    onestop_feed_id: statics.get(&feed.id).unwrap_or(&feed.id).clone(),
+                    kind,
+                    url: url.to_string(),
+                });
+            }
+        }
+    }
+    sources
+}
+
+async fn fetch(client: &reqwest::Client, url: &str) -> Result<FeedMessage, FetchError> {
+    let bytes = client.get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(FetchError::Http)?
+        .bytes()
+        .await
+        .map_err(FetchError::Http)?;
+    FeedMessage::decode(bytes).map_err(FetchError::Decode)
+}
+
+/// Seconds since the epoch as a timestamp, ignoring the zero that producers
+/// write for a missing time.
+fn timestamp(seconds: Option<u64>) -> Option<DateTime<Utc>> {
+    seconds.filter(|seconds| *seconds > 0).and_then(|seconds| DateTime::from_timestamp(seconds as i64, 0))
+}
+
+fn start_date(date: Option<&String>) -> Option<NaiveDate> {
+    date.and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok())
+}
+
+async fn makedb(client: &Client) -> Result<(), tokio_postgres::Error> {
+    client.batch_execute("
+        CREATE SCHEMA IF NOT EXISTS gtfs_rt;
+
+        CREATE TABLE IF NOT EXISTS gtfs_rt.feeds (
+            realtime_feed_id text NOT NULL,
+            kind text NOT NULL,
+            onestop_feed_id text NOT NULL,
+            url text NOT NULL,
+            feed_version_id bigint NULL,
+            header_timestamp timestamptz NULL,
+            fetched_at timestamptz NOT NULL DEFAULT now(),
+            entity_count integer NOT NULL,
+            PRIMARY KEY (realtime_feed_id, kind)
+        );
+
+        CREATE TABLE IF NOT EXISTS gtfs_rt.trip_updates (
+            realtime_feed_id text NOT NULL,
+            entity_id text NOT NULL,
+            onestop_feed_id text NOT NULL,
+            trip_id text NULL,
+            route_id text NULL,
+            direction_id integer NULL,
+            start_time text NULL,
+            start_date date NULL,
+            schedule_relationship integer NULL,
+            vehicle_id text NULL,
+            vehicle_label text NULL,
+            timestamp timestamptz NULL,
+            delay integer NULL,
+            trip_matched boolean NOT NULL DEFAULT false,
+            PRIMARY KEY (realtime_feed_id, entity_id)
+        );
+        CREATE INDEX IF NOT EXISTS trip_updates_trip ON gtfs_rt.trip_updates (onestop_feed_id, trip_id);
+
+        CREATE TABLE IF NOT EXISTS gtfs_rt.stop_time_updates (
+            realtime_feed_id text NOT NULL,
+            entity_id text NOT NULL,
+            position integer NOT NULL,
+            onestop_feed_id text NOT NULL,
+            trip_id text NULL,
+            stop_sequence integer NULL,
+            stop_id text NULL,
+            arrival_time timestamptz NULL,
+            arrival_delay integer NULL,
+            departure_time timestamptz NULL,
+            departure_delay integer NULL,
+            schedule_relationship integer NULL,
+            stop_matched boolean NOT NULL DEFAULT false,
+            PRIMARY KEY (realtime_feed_id, entity_id, position),
+            FOREIGN KEY (realtime_feed_id, entity_id) REFERENCES gtfs_rt.trip_updates(realtime_feed_id, entity_id) ON DELETE CASCADE ON UPDATE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS stop_time_updates_stop ON gtfs_rt.stop_time_updates (onestop_feed_id, stop_id);
+
+        CREATE TABLE IF NOT EXISTS gtfs_rt.vehicle_positions (
+            realtime_feed_id text NOT NULL,
+            entity_id text NOT NULL,
+            onestop_feed_id text NOT NULL,
+            trip_id text NULL,
+            route_id text NULL,
+            direction_id integer NULL,
+            start_date date NULL,
+            vehicle_id text NULL,
+            vehicle_label text NULL,
+            latitude double precision NULL,
+            longitude double precision NULL,
+            vehicle_geom GEOMETRY(POINT, 4326) GENERATED ALWAYS AS (ST_SetSRID(ST_MakePoint(longitude, latitude), 4326)) STORED,
+            bearing real NULL,
+            speed real NULL,
+            current_stop_sequence integer NULL,
+            stop_id text NULL,
+            current_status integer NULL,
+            timestamp timestamptz NULL,
+            congestion_level integer NULL,
+            occupancy_status integer NULL,
+            trip_matched boolean NOT NULL DEFAULT false,
+            stop_matched boolean NOT NULL DEFAULT false,
+            PRIMARY KEY (realtime_feed_id, entity_id)
+        );
+        CREATE INDEX IF NOT EXISTS vehicle_positions_geom ON gtfs_rt.vehicle_positions USING GIST (vehicle_geom);
+
+        CREATE TABLE IF NOT EXISTS gtfs_rt.alerts (
+            realtime_feed_id text NOT NULL,
+            entity_id text NOT NULL,
+            onestop_feed_id text NOT NULL,
+            cause integer NULL,
+            effect integer NULL,
+            severity_level integer NULL,
+            header_text text NULL,
+            description_text text NULL,
+            url text NULL,
+            active_periods tstzrange[] NOT NULL,
+            PRIMARY KEY (realtime_feed_id, entity_id)
+        );
+
+        CREATE TABLE IF NOT EXISTS gtfs_rt.alert_entities (
+            realtime_feed_id text NOT NULL,
+            entity_id text NOT NULL,
+            position integer NOT NULL,
+            onestop_feed_id text NOT NULL,
+            agency_id text NULL,
+            route_id text NULL,
+            route_type integer NULL,
+            direction_id integer NULL,
+            trip_id text NULL,
+            stop_id text NULL,
+            route_matched boolean NOT NULL DEFAULT false,
+            trip_matched boolean NOT NULL DEFAULT false,
+            stop_matched boolean NOT NULL DEFAULT false,
+            PRIMARY KEY (realtime_feed_id, entity_id, position),
+            FOREIGN KEY (realtime_feed_id, entity_id) REFERENCES gtfs_rt.alerts(realtime_feed_id, entity_id) ON DELETE CASCADE ON UPDATE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS alert_entities_route ON gtfs_rt.alert_entities (onestop_feed_id, route_id);
+        CREATE INDEX IF NOT EXISTS alert_entities_stop ON gtfs_rt.alert_entities (onestop_feed_id, stop_id);
+    ").await
+}
+
+/// Replaces what is stored for `source` with `message` and returns the
+/// number of entities stored. Deleted entities are left out.
+async fn store(client: &mut Client, source: &Source, message: &FeedMessage) -> Result<u64, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+    let feed = &source.realtime_feed_id;
+    let onestop_feed_id = &source.onestop_feed_id;
+    transaction
+        .execute(&format!("DELETE FROM gtfs_rt.{} WHERE realtime_feed_id = $1", source.kind.name()), &[feed])
+        .await?;
+    let entities = message.entity.iter().filter(|entity| !entity.is_deleted.unwrap_or(false));
+    let mut count = 0;
+    match source.kind {
+        Kind::TripUpdates => {
+            let trip_update = transaction.prepare("
+                INSERT INTO gtfs_rt.trip_updates (realtime_feed_id, entity_id, onestop_feed_id, trip_id, route_id, direction_id, start_time, start_date, schedule_relationship, vehicle_id, vehicle_label, timestamp, delay)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+                ON CONFLICT DO NOTHING
+            ").await?;
+            let stop_time_update = transaction.prepare("
+                INSERT INTO gtfs_rt.stop_time_updates (realtime_feed_id, entity_id, position, onestop_feed_id, trip_id, stop_sequence, stop_id, arrival_time, arrival_delay, departure_time, departure_delay, schedule_relationship)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, to_timestamp($8), $9, to_timestamp($10), $11, $12)
+                ON CONFLICT DO NOTHING
+            ").await?;
+            for entity in entities {
+                let Some(update) = &entity.trip_update else { continue };
+                let trip = &update.trip;
+                let vehicle = update.vehicle.as_ref();
+                count += transaction.execute(&trip_update, &[
+                    feed,
+                    &entity.id,
+                    onestop_feed_id,
+                    &trip.trip_id,
+                    &trip.route_id,
+                    &trip.direction_id.map(|direction| direction as i32),
+                    &trip.start_time,
+                    &start_date(trip.start_date.as_ref()),
+                    &trip.schedule_relationship,
+                    &vehicle.and_then(|vehicle| vehicle.id.as_ref()),
+                    &vehicle.and_then(|vehicle| vehicle.label.as_ref()),
+                    &timestamp(update.timestamp),
+                    &update.delay,
+                ]).await?;
+                for (position, stop) in update.stop_time_update.iter().enumerate() {
+                    let arrival = stop.arrival.as_ref();
+                    let departure = stop.departure.as_ref();
+                    transaction.execute(&stop_time_update, &[
+                        feed,
+                        &entity.id,
+                        &(position as i32),
+                        onestop_feed_id,
+                        &trip.trip_id,
+                        &stop.stop_sequence.map(|sequence| sequence as i32),
+                        &stop.stop_id,
+                        &arrival.and_then(|event| event.time).map(|time| time as f64),
+                        &arrival.and_then(|event| event.delay),
+                        &departure.and_then(|event| event.time).map(|time| time as f64),
+                        &departure.and_then(|event| event.delay),
+                        &stop.schedule_relationship,
+                    ]).await?;
+                }
+            }
+        }
+        Kind::VehiclePositions => {
+            let vehicle_position = transaction.prepare("
+                INSERT INTO gtfs_rt.vehicle_positions (realtime_feed_id, entity_id, onestop_feed_id, trip_id, route_id, direction_id, start_date, vehicle_id, vehicle_label, latitude, longitude, bearing, speed, current_stop_sequence, stop_id, current_status, timestamp, congestion_level, occupancy_status)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)
+                ON CONFLICT DO NOTHING
+            ").await?;
+            for entity in entities {
+                let Some(vehicle) = &entity.vehicle else { continue };
+                let trip = vehicle.trip.as_ref();
+                let descriptor = vehicle.vehicle.as_ref();
+                let position = vehicle.position.as_ref();
+                count += transaction.execute(&vehicle_position, &[
+                    feed,
+                    &entity.id,
+                    onestop_feed_id,
+                    &trip.and_then(|trip| trip.trip_id.as_ref()),
+                    &trip.and_then(|trip| trip.route_id.as_ref()),
+                    &trip.and_then(|trip| trip.direction_id).map(|direction| direction as i32),
+                    &start_date(trip.and_then(|trip| trip.start_date.as_ref())),
+                    &descriptor.and_then(|descriptor| descriptor.id.as_ref()),
+                    &descriptor.and_then(|descriptor| descriptor.label.as_ref()),
+                    &position.map(|position| position.latitude as f64),
+                    &position.map(|position| position.longitude as f64),
+                    &position.and_then(|position| position.bearing),
+                    &position.and_then(|position| position.speed),
+                    &vehicle.current_stop_sequence.map(|sequence| sequence as i32),
+                    &vehicle.stop_id,
+                    &vehicle.current_status,
+                    &timestamp(vehicle.timestamp),
+                    &vehicle.congestion_level,
+                    &vehicle.occupancy_status,
+                ]).await?;
+            }
+        }
+        Kind::Alerts => {
+            let alert = transaction.prepare("
+                INSERT INTO gtfs_rt.alerts (realtime_feed_id, entity_id, onestop_feed_id, cause, effect, severity_level, header_text, description_text, url, active_periods)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, ARRAY(
+                    SELECT tstzrange(period.start, period.end) FROM unnest($10::timestamptz[], $11::timestamptz[]) AS period(start, \"end\")
+                ))
+                ON CONFLICT DO NOTHING
+            ").await?;
+            let informed_entity = transaction.prepare("
+                INSERT INTO gtfs_rt.alert_entities (realtime_feed_id, entity_id, position, onestop_feed_id, agency_id, route_id, route_type, direction_id, trip_id, stop_id)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                ON CONFLICT DO NOTHING
+            ").await?;
+            for entity in entities {
+                let Some(message) = &entity.alert else { continue };
+                let starts = message.active_period.iter().map(|period| timestamp(period.start)).collect::<Vec<_>>();
+                let ends = message.active_period.iter().map(|period| timestamp(period.end)).collect::<Vec<_>>();
+                count += transaction.execute(&alert, &[
+                    feed,
+                    &entity.id,
+                    onestop_feed_id,
+                    &message.cause,
+                    &message.effect,
+                    &message.severity_level,
+                    &message.header_text.as_ref().and_then(|text| text.text()),
+                    &message.description_text.as_ref().and_then(|text| text.text()),
+                    &message.url.as_ref().and_then(|text| text.text()),
+                    &starts,
+                    &ends,
+                ]).await?;
+                for (position, selector) in message.informed_entity.iter().enumerate() {
+                    let trip = selector.trip.as_ref();
+                    transaction.execute(&informed_entity, &[
+                        feed,
+                        &entity.id,
+                        &(position as i32),
+                        onestop_feed_id,
+                        &selector.agency_id,
+                        &selector.route_id.as_ref().or(trip.and_then(|trip| trip.route_id.as_ref())),
+                        &selector.route_type,
+                        &selector.direction_id.map(|direction| direction as i32),
+                        &trip.and_then(|trip| trip.trip_id.as_ref()),
+                        &selector.stop_id,
+                    ]).await?;
+                }
+            }
+        }
+    }
+
+    // Match against the latest import of the static feed. Stop time updates
+    // that only give a stop_sequence get the stop_id of the scheduled stop.
+    let feed_version_id: Option<i64> = transaction
+        .query_one("SELECT gtfs.latest_version($1)", &[onestop_feed_id])
+        .await?
+        .get(0);
+    let matches: &[&str] = match source.kind {
+        Kind::TripUpdates => &["
+            UPDATE gtfs_rt.trip_updates SET trip_matched = EXISTS (
+                SELECT 1 FROM gtfs.trips
+                WHERE trips.onestop_feed_id = $2 AND trips.feed_version_id = $3 AND trips.trip_id = trip_updates.trip_id
+            )
+            WHERE realtime_feed_id = $1
+        ", "
+            UPDATE gtfs_rt.stop_time_updates SET stop_id = stop_times.stop_id
+            FROM gtfs.stop_times
+            WHERE stop_time_updates.realtime_feed_id = $1 AND stop_time_updates.stop_id IS NULL
+                AND stop_times.onestop_feed_id = $2 AND stop_times.feed_version_id = $3
+                AND stop_times.trip_id = stop_time_updates.trip_id AND stop_times.stop_sequence = stop_time_updates.stop_sequence
+        ", "
+            UPDATE gtfs_rt.stop_time_updates SET stop_matched = EXISTS (
+                SELECT 1 FROM gtfs.stops
+                WHERE stops.onestop_feed_id = $2 AND stops.feed_version_id = $3 AND stops.stop_id = stop_time_updates.stop_id
+            )
+            WHERE realtime_feed_id = $1
+        "],
+        Kind::VehiclePositions => &["
+            UPDATE gtfs_rt.vehicle_positions SET
+                trip_matched = EXISTS (
+                    SELECT 1 FROM gtfs.trips
+                    WHERE trips.onestop_feed_id = $2 AND trips.feed_version_id = $3 AND trips.trip_id = vehicle_positions.trip_id
+                ),
+                stop_matched = EXISTS (
+                    SELECT 1 FROM gtfs.stops
+                    WHERE stops.onestop_feed_id = $2 AND stops.feed_version_id = $3 AND stops.stop_id = vehicle_positions.stop_id
+                )
+            WHERE realtime_feed_id = $1
+        "],
+        Kind::Alerts => &["
+            UPDATE gtfs_rt.alert_entities SET
+                route_matched = EXISTS (
+                    SELECT 1 FROM gtfs.routes
+                    WHERE routes.onestop_feed_id = $2 AND routes.feed_version_id = $3 AND routes.route_id = alert_entities.route_id
+                ),
+                trip_matched = EXISTS (
+                    SELECT 1 FROM gtfs.trips
+                    WHERE trips.onestop_feed_id = $2 AND trips.feed_version_id = $3 AND trips.trip_id = alert_entities.trip_id
+                ),
+                stop_matched = EXISTS (
+                    SELECT 1 FROM gtfs.stops
+                    WHERE stops.onestop_feed_id = $2 AND stops.feed_version_id = $3 AND stops.stop_id = alert_entities.stop_id
+                )
+            WHERE realtime_feed_id = $1
+        "],
+    };
+    for statement in matches {
+        transaction.execute(*statement, &[feed, onestop_feed_id, &feed_version_id]).await?;
+    }
+
+    transaction.execute("
+        INSERT INTO gtfs_rt.feeds (realtime_feed_id, kind, onestop_feed_id, url, feed_version_id, header_timestamp, entity_count)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (realtime_feed_id, kind) DO UPDATE SET
+            onestop_feed_id = EXCLUDED.onestop_feed_id,
+            url = EXCLUDED.url,
+            feed_version_id = EXCLUDED.feed_version_id,
+            header_timestamp = EXCLUDED.header_timestamp,
+            fetched_at = now(),
+            entity_count = EXCLUDED.entity_count
+    ", &[feed, &source.kind.name(), onestop_feed_id, &source.url, &feed_version_id, &timestamp(message.header.timestamp), &(count as i32)]).await?;
+    transaction.commit().await?;
+    Ok(count)
+}
+
+async fn poll(http: reqwest::Client, pool: Pool, source: Source) {
+    let label = format!("{} {}", source.realtime_feed_id, source.kind.name());
+    let message = match fetch(&http, &source.url).await {
+        Ok(message) => message,
+        Err(e) => {
+            eprintln!("{}: {}", label, e);
+            return;
+        }
+    };
+    let stored = match pool.get().await {
+        Ok(mut client) => store(&mut client, &source, &message).await.map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    match stored {
+        Ok(count) => println!("{}: {} entities", label, count),
+        Err(e) => eprintln!("{}: cannot store: {}", label, e),
+    }
+}
+
+/// Whether gtfs-schema has created the gtfs schema, whose latest_version
+/// `store` looks up on every poll.
+async fn has_gtfs(client: &Client) -> Result<bool, tokio_postgres::Error> {
+    let row = client.query_one("SELECT to_regprocedure('gtfs.latest_version(text)') IS NOT NULL", &[]).await?;
+    Ok(row.get(0))
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match arguments::parse(std::env::args()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Cannot parse the arguments: {}", e);
+            process::exit(1);
+        }
+    };
+    // Directory of DMFR files listing the feeds, as used by download.
+    let dir = args.get::<String>("dir").unwrap_or("transitland-atlas/feeds/".to_string());
+    // Seconds between polls of every feed.
+    let interval = args.get::<u64>("interval").unwrap_or(30).max(1);
+    // Poll every feed a single time and exit.
+    let once = args.get::<bool>("once").unwrap_or(false);
+
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let pool = match config.pool() {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("Cannot create the database pool: {}", e);
+            process::exit(1);
+        }
+    };
+    let imported = match pool.get().await {
+        Ok(client) => has_gtfs(&client).await.map_err(|e| e.as_db_error().map_or(e.to_string(), |e| e.to_string())),
+        Err(e) => Err(e.to_string()),
+    };
+    match imported {
+        Ok(true) => {}
+        Ok(false) => {
+            eprintln!("No gtfs schema to match trips against, run gtfs-schema first");
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Cannot check for the gtfs schema: {}", e);
+            process::exit(1);
+        }
+    }
+    let created = match pool.get().await {
+        Ok(client) => makedb(&client).await.map_err(|e| e.as_db_error().map_or(e.to_string(), |e| e.to_string())),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = created {
+        eprintln!("Cannot create the gtfs_rt schema: {}", e);
+        process::exit(1);
+    }
+
+    let sources = sources(Path::new(&dir));
+    if sources.is_empty() {
+        eprintln!("No realtime feeds found in {}", dir);
+        process::exit(1);
+    }
+    let http = reqwest::ClientBuilder::new()
+        .gzip(true)
+        .deflate(true)
+        .brotli(true)
+        .use_rustls_tls()
+        .timeout(Duration::from_secs(interval.max(10)))
+        .build();
+    let http = match http {
+        Ok(http) => http,
+        Err(e) => {
+            eprintln!("Cannot create the HTTP client: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let mut futs = FuturesUnordered::new();
+        for source in &sources {
+            futs.push(task::spawn(poll(http.clone(), pool.clone(), source.clone())));
+            if futs.len() == config.pool_size {
+                if let Some(Err(e)) = futs.next().await {
+                    eprintln!("poll task failed: {}", e);
+                }
+            }
+        }
+        while let Some(result) = futs.next().await {
+            if let Err(e) = result {
+                eprintln!("poll task failed: {}", e);
+            }
+        }
+        if once {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+
+    fn fixture(name: &str) -> Vec<u8> {
+        fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/realtime").join(name)).unwrap()
+    }
+
+    /// Serves each fixture at `/<name>` and answers 404 for anything else,
+    /// returning the base URL.
+    async fn stub(fixtures: &'static [&'static str]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut socket, _)) = listener.accept().await else { return };
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buffer = [0; 1024];
+                    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                        match socket.read(&mut buffer).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(read) => request.extend_from_slice(&buffer[..read]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request);
+                    let path = request.split_whitespace().nth(1).unwrap_or("/").trim_start_matches('/').to_string();
+                    let response = match fixtures.iter().find(|name| **name == path) {
+                        Some(name) => {
+                            let body = fixture(name);
+                            let mut response = format!(
+                                "HTTP/1.1 200 OK\r\ncontent-type: application/x-protobuf\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                                body.len()
+                            ).into_bytes();
+                            response.extend(body);
+                            response
+                        }
+                        None => b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_vec(),
+                    };
+                    let _ = socket.write_all(&response).await;
+                });
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn decodes_trip_updates() {
+        let base = stub(&["trip_updates.pb"]).await;
+        let message = fetch(&reqwest::Client::new(), &format!("{}/trip_updates.pb", base)).await.unwrap();
+        assert_eq!(message.header.gtfs_realtime_version, "2.0");
+        assert!(timestamp(message.header.timestamp).is_some());
+
+        let updates = message.entity.iter().filter_map(|entity| entity.trip_update.as_ref()).collect::<Vec<_>>();
+        assert_eq!(updates.len(), 2);
+        let update = updates.iter().find(|update| update.trip.trip_id.as_deref() == Some("WK-0800")).unwrap();
+        assert_eq!(start_date(update.trip.start_date.as_ref()), NaiveDate::from_ymd_opt(2026, 10, 15));
+        assert_eq!(update.stop_time_update.len(), 2);
+        assert_eq!(update.stop_time_update[0].stop_id.as_deref(), Some("P1"));
+        assert_eq!(update.stop_time_update[0].departure.as_ref().and_then(|event| event.delay), Some(120));
+        // The second stop is only given by its sequence.
+        assert_eq!(update.stop_time_update[1].stop_id, None);
+        assert_eq!(update.stop_time_update[1].stop_sequence, Some(2));
+    }
+
+    #[tokio::test]
+    async fn decodes_vehicle_positions() {
+        let base = stub(&["vehicle_positions.pb"]).await;
+        let message = fetch(&reqwest::Client::new(), &format!("{}/vehicle_positions.pb", base)).await.unwrap();
+        let vehicles = message.entity.iter().filter_map(|entity| entity.vehicle.as_ref()).collect::<Vec<_>>();
+        assert_eq!(vehicles.len(), 1);
+        let vehicle = vehicles[0];
+        assert_eq!(vehicle.trip.as_ref().and_then(|trip| trip.trip_id.as_deref()), Some("WK-0800"));
+        assert_eq!(vehicle.vehicle.as_ref().and_then(|vehicle| vehicle.id.as_deref()), Some("bus-42"));
+        let position = vehicle.position.as_ref().unwrap();
+        assert!((position.latitude - 41.881).abs() < 1e-4);
+        assert!((position.longitude + 87.63).abs() < 1e-4);
+        assert_eq!(vehicle.stop_id.as_deref(), Some("P1"));
+    }
+
+    #[tokio::test]
+    async fn decodes_alerts() {
+        let base = stub(&["alerts.pb"]).await;
+        let message = fetch(&reqwest::Client::new(), &format!("{}/alerts.pb", base)).await.unwrap();
+        let alerts = message.entity.iter().filter_map(|entity| entity.alert.as_ref()).collect::<Vec<_>>();
+        assert_eq!(alerts.len(), 1);
+        let alert = alerts[0];
+        assert_eq!(alert.header_text.as_ref().and_then(|text| text.text()), Some("Platform 1 closed"));
+        assert_eq!(alert.active_period.len(), 1);
+        assert!(timestamp(alert.active_period[0].start).is_some());
+        assert_eq!(timestamp(alert.active_period[0].end), None);
+        let routes = alert.informed_entity.iter().filter_map(|selector| selector.route_id.as_deref()).collect::<Vec<_>>();
+        let stops = alert.informed_entity.iter().filter_map(|selector| selector.stop_id.as_deref()).collect::<Vec<_>>();
+        assert_eq!(routes, ["R1"]);
+        assert_eq!(stops, ["STA"]);
+    }
+
+    #[tokio::test]
+    async fn fails_on_missing_and_malformed_feeds() {
+        let base = stub(&["not_protobuf.pb"]).await;
+        let http = reqwest::Client::new();
+        assert!(matches!(fetch(&http, &format!("{}/missing.pb", base)).await, Err(FetchError::Http(_))));
+        assert!(matches!(fetch(&http, &format!("{}/not_protobuf.pb", base)).await, Err(FetchError::Decode(_))));
+    }
+
+    #[test]
+    fn associates_realtime_feeds_with_their_static_feed() {
+        let registry: DistributedMobilityFeedRegistry = serde_json::from_str(r#"{
+            "feeds": [
+                {"id": "f-board", "spec": "gtfs", "urls": {"static_current": "https://example.com/gtfs.zip"}},
+                {"id": "f-board~rt", "spec": "gtfs-rt", "urls": {
+                    "realtime_trip_updates": "https://example.com/trip_updates.pb",
+                    "realtime_alerts": "https://example.com/alerts.pb"
+                }},
+                {"id": "f-orphan~rt", "spec": "gtfs-rt", "urls": {"realtime_vehicle_positions": "https://example.com/vehicles.pb"}}
+            ],
+            "operators": [
+                {"onestop_id": "o-board", "name": "Board", "associated_feeds": [
+                    {"feed_onestop_id": "f-board"},
+                    {"feed_onestop_id": "f-board~rt"}
+                ]}
+            ]
+        }"#).unwrap();
+        let sources = registry_sources(&registry);
+        let found = sources
+            .iter()
+            .map(|source| (source.realtime_feed_id.as_str(), source.onestop_feed_id.as_str(), source.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(found, [
+            ("f-board~rt", "f-board", Kind::TripUpdates),
+            ("f-board~rt", "f-board", Kind::Alerts),
+            ("f-orphan~rt", "f-orphan~rt", Kind::VehiclePositions),
+        ]);
+    }
+}
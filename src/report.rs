@@ -0,0 +1,185 @@
+//! The outcome of importing every feed in a run, printed once all feeds are
+//! done and optionally written out as JSON.
+
+use serde_derive::Serialize;
+use std::{error::Error, fmt, fs, io, path::Path};
+
+#[derive(Debug)]
+pub enum ImportError {
+    /// The feed could not be read, so nothing was loaded.
+    Parse(gtfs_structures::Error),
+    /// A statement failed and the feed's transaction was rolled back.
+    /// `table` is the table being loaded at the time, if any.
+    Load { table: Option<&'static str>, error: tokio_postgres::Error },
+}
+
+impl From<tokio_postgres::Error> for ImportError {
+    fn from(error: tokio_postgres::Error) -> ImportError {
+        ImportError::Load { table: None, error }
+    }
+}
+
+impl From<gtfs_structures::Error> for ImportError {
+    fn from(error: gtfs_structures::Error) -> ImportError {
+        ImportError::Parse(error)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Status {
+    Succeeded { feed_version_id: i64, rows: u64 },
+    /// Skipped by an incremental import since the zip has not changed.
+    Unchanged,
+    ParseFailed { error: String },
+    LoadFailed { table: Option<&'static str>, error: String },
+}
+
+impl From<Result<Status, ImportError>> for Status {
+    fn from(result: Result<Status, ImportError>) -> Status {
+        match result {
+            Ok(status) => status,
+            Err(ImportError::Parse(error)) => Status::ParseFailed { error: describe(&error) },
+            Err(ImportError::Load { table, error }) => Status::LoadFailed { table, error: describe(&error) },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct FeedReport {
+    pub onestop_feed_id: String,
+    #[serde(flatten)]
+    pub status: Status,
+}
+
+impl fmt::Display for FeedReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.status {
+            Status::Succeeded { feed_version_id, rows } => {
+                write!(f, "{}: succeeded, version {} with {} rows", self.onestop_feed_id, feed_version_id, rows)
+            }
+            Status::Unchanged => write!(f, "{}: unchanged", self.onestop_feed_id),
+            Status::ParseFailed { error } => write!(f, "{}: failed to parse: {}", self.onestop_feed_id, error),
+            Status::LoadFailed { table: Some(table), error } => {
+                write!(f, "{}: failed to load {}: {}", self.onestop_feed_id, table, error)
+            }
+            Status::LoadFailed { table: None, error } => write!(f, "{}: failed to load: {}", self.onestop_feed_id, error),
+        }
+    }
+}
+
+#[derive(Default, Serialize)]
+pub struct Report {
+    pub feeds: Vec<FeedReport>,
+}
+
+impl Report {
+    pub fn failed(&self) -> usize {
+        self.feeds
+            .iter()
+            .filter(|feed| matches!(feed.status, Status::ParseFailed { .. } | Status::LoadFailed { .. }))
+            .count()
+    }
+
+    /// Whether more than `threshold` (a fraction from 0 to 1) of the feeds
+    /// failed.
+    pub fn exceeds(&self, threshold: f64) -> bool {
+        self.failed() as f64 > threshold * self.feeds.len() as f64
+    }
+
+    pub fn print(&self) {
+        println!("Imported {} feeds, {} failed", self.feeds.len(), self.failed());
+        for feed in &self.feeds {
+            println!("  {}", feed);
+        }
+    }
+
+    pub fn write_json(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+}
+
+/// An error with all of its sources, since neither gtfs_structures nor
+/// tokio_postgres include the underlying cause in their own message.
+fn describe(error: &dyn Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(statuses: Vec<Status>) -> Report {
+        Report {
+            feeds: statuses
+                .into_iter()
+                .enumerate()
+                .map(|(i, status)| FeedReport { onestop_feed_id: format!("f-{}", i), status })
+                .collect(),
+        }
+    }
+
+    fn succeeded() -> Status {
+        Status::Succeeded { feed_version_id: 1, rows: 10 }
+    }
+
+    fn failed() -> Status {
+        Status::ParseFailed { error: "bad zip".to_string() }
+    }
+
+    #[derive(Debug)]
+    struct Wrapped(io::Error);
+
+    impl fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "cannot read feed")
+        }
+    }
+
+    impl Error for Wrapped {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn failures_past_the_threshold() {
+        assert!(!report(vec![succeeded(), Status::Unchanged]).exceeds(0.0));
+        assert!(report(vec![succeeded(), failed()]).exceeds(0.0));
+        let half = report(vec![succeeded(), succeeded(), failed(), Status::LoadFailed { table: None, error: "gone".to_string() }]);
+        assert_eq!(half.failed(), 2);
+        assert!(!half.exceeds(0.5));
+        assert!(half.exceeds(0.49));
+        assert!(!report(vec![]).exceeds(0.0));
+        assert!(!report(vec![failed()]).exceeds(1.0));
+    }
+
+    #[test]
+    fn statuses_from_results() {
+        let parse = Status::from(Err(ImportError::Parse(gtfs_structures::Error::MissingFile("stops.txt".to_string()))));
+        assert!(matches!(parse, Status::ParseFailed { error } if error.contains("stops.txt")));
+        assert!(matches!(Status::from(Ok(Status::Unchanged)), Status::Unchanged));
+        let feed = FeedReport { onestop_feed_id: "f-x".to_string(), status: Status::LoadFailed { table: Some("stops"), error: "duplicate key".to_string() } };
+        assert_eq!(feed.to_string(), "f-x: failed to load stops: duplicate key");
+        assert_eq!(
+            serde_json::to_value(&feed).unwrap(),
+            serde_json::json!({ "onestop_feed_id": "f-x", "status": "load_failed", "table": "stops", "error": "duplicate key" }),
+        );
+        let feed = FeedReport { onestop_feed_id: "f-y".to_string(), status: succeeded() };
+        assert_eq!(feed.to_string(), "f-y: succeeded, version 1 with 10 rows");
+    }
+
+    #[test]
+    fn descriptions_include_every_source() {
+        let error = Wrapped(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+        assert_eq!(describe(&error), "cannot read feed: no such file");
+        assert_eq!(describe(&io::Error::other("alone")), "alone");
+    }
+}
@@ -0,0 +1,295 @@
+//! The vector tile functions, generated from the layers in `tiles.toml` and
+//! installed by `martin` and by every run of `gtfs-schema`.
+//!
+//! The layers read `gtfs.tile_shapes` and `gtfs.tile_stops`, the materialized
+//! views holding the latest import of every feed with the routes and route
+//! types each shape and stop serves. Each function also carries a TileJSON
+//! comment with its zooms and fields, which `web` and Martin both pick up.
+
+use crate::config::ConfigError;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use std::{collections::HashSet, fs, path::{Path, PathBuf}};
+use tokio_postgres::Client;
+
+const DEFAULT_LAYERS: &str = include_str!("../tiles.toml");
+const MAX_ZOOM: u32 = 22;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    layer: Vec<Layer>,
+}
+
+/// One layer of `tiles.toml`, see the comment at the top of that file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Layer {
+    pub name: String,
+    pub table: String,
+    pub geometry: String,
+    #[serde(default)]
+    pub route_types: Vec<i32>,
+    #[serde(default)]
+    pub exclude_route_types: Vec<i32>,
+    #[serde(default = "default_route_type_column")]
+    pub route_type_column: String,
+    #[serde(default)]
+    pub route_type_arrays: Vec<String>,
+    pub filter: Option<String>,
+    pub attributes: Vec<String>,
+    #[serde(default)]
+    pub minzoom: u32,
+    #[serde(default = "default_maxzoom")]
+    pub maxzoom: u32,
+}
+
+fn default_route_type_column() -> String {
+    "route_type".to_string()
+}
+
+fn default_maxzoom() -> u32 {
+    MAX_ZOOM
+}
+
+/// Reads the layers from `path`, or the built-in `tiles.toml` without one.
+pub fn load(path: Option<&Path>) -> Result<Vec<Layer>, ConfigError> {
+    match path {
+        Some(path) => {
+            let contents = fs::read_to_string(path).map_err(|e| ConfigError::File(path.to_path_buf(), e.to_string()))?;
+            parse(&contents, path)
+        }
+        None => parse(DEFAULT_LAYERS, Path::new("tiles.toml")),
+    }
+}
+
+fn parse(contents: &str, path: &Path) -> Result<Vec<Layer>, ConfigError> {
+    let file: File = toml::from_str(contents).map_err(|e| ConfigError::File(PathBuf::from(path), e.to_string()))?;
+    let mut names = HashSet::new();
+    for layer in &file.layer {
+        layer.validate()?;
+        if !names.insert(layer.name.as_str()) {
+            return Err(ConfigError::Invalid("tile layer", format!("{} is defined twice", layer.name)));
+        }
+    }
+    Ok(file.layer)
+}
+
+/// Whether `name` can go into the SQL as is: lower case, so it never needs
+/// quoting.
+fn identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+impl Layer {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |why: String| Err(ConfigError::Invalid("tile layer", format!("{}: {}", self.name, why)));
+        if !identifier(&self.name) {
+            return invalid("the name must be a lower case SQL identifier".to_string());
+        }
+        if !self.table.split('.').all(identifier) || self.table.split('.').count() > 2 {
+            return invalid(format!("invalid table {}", self.table));
+        }
+        if let Some(column) = [&self.geometry, &self.route_type_column].into_iter().chain(&self.route_type_arrays).find(|column| !identifier(column)) {
+            return invalid(format!("invalid column {}", column));
+        }
+        if self.attributes.is_empty() {
+            return invalid("no attributes".to_string());
+        }
+        if let Some(attribute) = self.attributes.iter().find(|attribute| !identifier(field(attribute))) {
+            return invalid(format!("{} needs to be a column or end in AS <name>", attribute));
+        }
+        if self.minzoom > self.maxzoom || self.maxzoom > MAX_ZOOM {
+            return invalid(format!("zooms {} to {} are not within 0 to {}", self.minzoom, self.maxzoom, MAX_ZOOM));
+        }
+        Ok(())
+    }
+
+    /// Matches a list of route types against the route type column, or
+    /// against any of the route type arrays.
+    fn route_type_condition(&self, route_types: &[i32]) -> String {
+        let list = route_types.iter().map(i32::to_string).collect::<Vec<_>>().join(", ");
+        if self.route_type_arrays.is_empty() {
+            format!("{} IN ({})", self.route_type_column, list)
+        } else {
+            let matches = self.route_type_arrays
+                .iter()
+                .map(|column| format!("ARRAY[{}]::integer[] && {}::integer[]", list, column))
+                .collect::<Vec<_>>();
+            format!("({})", matches.join(" OR "))
+        }
+    }
+
+    /// The query for one tile, with z, x and y given as SQL expressions.
+    fn select(&self, z: &str, x: &str, y: &str) -> String {
+        let envelope = format!("ST_TileEnvelope({}, {}, {})", z, x, y);
+        let mut conditions = vec![format!("{} && ST_Transform({}, 4326)", self.geometry, envelope)];
+        if !self.route_types.is_empty() {
+            conditions.push(self.route_type_condition(&self.route_types));
+        }
+        if !self.exclude_route_types.is_empty() {
+            conditions.push(format!("NOT {}", self.route_type_condition(&self.exclude_route_types)));
+        }
+        if let Some(filter) = &self.filter {
+            conditions.push(format!("({})", filter));
+        }
+        format!("
+            SELECT ST_AsMVT(tile, '{name}', 4096, 'geom') FROM (
+                SELECT
+                ST_AsMVTGeom(
+                    ST_Transform({geometry}, 3857),
+                    {envelope},
+                    4096, 64, true) AS geom,
+                    {attributes}
+                FROM {table}
+                WHERE {conditions}
+            ) as tile WHERE geom IS NOT NULL",
+            name = self.name,
+            geometry = self.geometry,
+            envelope = envelope,
+            attributes = self.attributes.join(", "),
+            table = self.table,
+            conditions = conditions.join(" AND "),
+        )
+    }
+
+    /// The names of the attributes in the tile.
+    pub fn fields(&self) -> Vec<&str> {
+        self.attributes.iter().map(|attribute| field(attribute)).collect()
+    }
+
+    fn tilejson(&self) -> Value {
+        let fields = self.fields().into_iter().map(|field| (field.to_string(), json!(""))).collect::<serde_json::Map<_, _>>();
+        json!({
+            "minzoom": self.minzoom,
+            "maxzoom": self.maxzoom,
+            "vector_layers": [{ "id": self.name, "fields": fields, "minzoom": self.minzoom, "maxzoom": self.maxzoom }],
+        })
+    }
+
+    /// The statements creating gtfs.<name>(z, x, y). Outside the layer's
+    /// zooms it returns NULL, an empty tile.
+    fn function(&self) -> String {
+        format!("
+            CREATE OR REPLACE
+            FUNCTION gtfs.{name}(z integer, x integer, y integer)
+            RETURNS bytea AS $$
+            BEGIN
+            IF z < {minzoom} OR z > {maxzoom} THEN
+                RETURN NULL;
+            END IF;
+            RETURN ({select});
+            END
+            $$ LANGUAGE plpgsql STABLE STRICT PARALLEL SAFE;
+            COMMENT ON FUNCTION gtfs.{name}(integer, integer, integer) IS '{comment}';
+        ",
+            name = self.name,
+            minzoom = self.minzoom,
+            maxzoom = self.maxzoom,
+            select = self.select("z", "x", "y"),
+            comment = self.tilejson().to_string().replace('\'', "''"),
+        )
+    }
+}
+
+/// The output name of an attribute, the part after its last `AS`.
+fn field(attribute: &str) -> &str {
+    let attribute = attribute.trim();
+    match attribute.to_ascii_lowercase().rfind(" as ") {
+        Some(at) => attribute[at + 4..].trim(),
+        None => attribute,
+    }
+}
+
+pub async fn install(client: &Client, layers: &[Layer]) -> Result<(), tokio_postgres::Error> {
+    for layer in layers {
+        client.batch_execute(&layer.function()).await?;
+    }
+    Ok(())
+}
+
+/// Prepares every layer's query without running it, so a layer reading a
+/// column or table that does not exist fails here rather than on its first
+/// tile. PL/pgSQL bodies are only compiled when called, installing alone
+/// would not catch these.
+#[allow(dead_code)] // Only martin --check runs it.
+pub async fn check<'a>(client: &Client, layers: &'a [Layer]) -> Vec<(&'a str, tokio_postgres::Error)> {
+    let mut failures = Vec::new();
+    for layer in layers {
+        if let Err(error) = client.prepare(&layer.select("$1::integer", "$2::integer", "$3::integer")).await {
+            failures.push((layer.name.as_str(), error));
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn layer(extra: &str) -> String {
+        format!("
+            [[layer]]
+            name = \"busonly\"
+            table = \"gtfs.tile_shapes\"
+            geometry = \"linestring\"
+            attributes = [\"shape_id\"]
+            {}
+        ", extra)
+    }
+
+    #[test]
+    fn default_layers_load() {
+        let layers = load(None).unwrap();
+        assert!(layers.iter().any(|layer| layer.name == "shapes"));
+        for layer in &layers {
+            assert!(layer.function().contains(&format!("ST_AsMVT(tile, '{}',", layer.name)));
+        }
+        let busstops = layers.iter().find(|layer| layer.name == "busstops").unwrap();
+        assert!(busstops.fields().contains(&"name"));
+    }
+
+    #[test]
+    fn invalid_layers_are_rejected() {
+        let path = Path::new("tiles.toml");
+        assert!(parse(&layer(""), path).is_ok());
+        assert!(parse(&format!("{}{}", layer(""), layer("")), path).is_err());
+        assert!(parse(&layer("minzoom = 14\nmaxzoom = 10"), path).is_err());
+        assert!(parse(&layer("maxzoom = 30"), path).is_err());
+        assert!(parse(&layer("route_type_arrays = [\"route_types; DROP TABLE x\"]"), path).is_err());
+        assert!(parse(&layer("unknown = 1"), path).is_err());
+        assert!(parse(&layer("").replace("\"shape_id\"", "\"REPLACE(name, 'a', '')\""), path).is_err());
+        assert!(parse(&layer("").replace("busonly", "bus-only"), path).is_err());
+    }
+
+    #[test]
+    fn route_type_filters() {
+        let layers = parse(&layer("exclude_route_types = [3, 11]"), Path::new("tiles.toml")).unwrap();
+        assert!(layers[0].select("z", "x", "y").contains("NOT route_type IN (3, 11)"));
+        let layers = parse(&layer("route_types = [4]\nroute_type_arrays = [\"route_types\", \"children_route_types\"]"), Path::new("tiles.toml")).unwrap();
+        assert!(layers[0].select("z", "x", "y").contains("(ARRAY[4]::integer[] && route_types::integer[] OR ARRAY[4]::integer[] && children_route_types::integer[])"));
+    }
+
+    /// Compiles every layer against the database in
+    /// `GTFS_SCHEMA_TEST_DATABASE_URL`, which gtfs-schema has imported into.
+    /// Skipped when that is unset, and failing when it is set but the
+    /// database cannot be reached or has no tile views.
+    #[tokio::test]
+    async fn every_layer_compiles() {
+        let Ok(url) = std::env::var("GTFS_SCHEMA_TEST_DATABASE_URL") else {
+            eprintln!("skipped: set GTFS_SCHEMA_TEST_DATABASE_URL to compile the layers");
+            return;
+        };
+        let args = ["test", "--database-url", &url].map(String::from);
+        let config = Config::load(&arguments::parse(args.into_iter()).unwrap()).unwrap();
+        let client = config.connect().await.unwrap_or_else(|e| panic!("cannot connect to {}: {}", url, e));
+        let row = client.query_one("SELECT to_regclass('gtfs.tile_shapes') IS NOT NULL AND to_regclass('gtfs.tile_stops') IS NOT NULL", &[]).await.unwrap();
+        assert!(row.get::<_, bool>(0), "no tile views in {}, run gtfs-schema first", url);
+        let layers = load(None).unwrap();
+        let failures = check(&client, &layers).await;
+        assert!(failures.is_empty(), "{:?}", failures);
+    }
+}
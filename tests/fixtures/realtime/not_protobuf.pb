@@ -0,0 +1 @@
+<html>Service unavailable</html>
\ No newline at end of file